@@ -5,6 +5,11 @@
 //     cap: usize,  // 分配内存的大小
 //     len: usize,  //  已经被初始化的元素个数
 // }
+//
+// NOTE: targets a pre-stabilization `allocator_api` (`std::heap::{Alloc,
+// Layout, Global}`, `std::alloc::oom`) from before the allocator traits were
+// redesigned. That API no longer exists on any current stable or nightly
+// toolchain, so this crate cannot be built or tested here as-is.
 #![feature(allocator_api)]
 
 use std::ptr::{NonNull, self};
@@ -52,14 +57,21 @@ pub struct Vec<T> {
 
 impl<T> Vec<T> {
     fn new() -> Self {
-        assert!(mem::size_of::<T>() != 0, "We're not ready to handle ZSTs");
-        Vec { ptr: NonNull::dangling(), len: 0, cap: 0 }
+        // !0 is usize::MAX. This branch should be stripped at compile time.
+        let cap = if mem::size_of::<T>() == 0 { !0 } else { 0 };
+        Vec { ptr: NonNull::dangling(), len: 0, cap: cap }
     }
 
 
     fn grow(&mut self) {
         // this is all pretty delicate, so let's say it's all unsafe
         unsafe {
+            let elem_size = mem::size_of::<T>();
+
+            // since we set the capacity to usize::MAX when elem_size is
+            // 0, getting to here necessarily means the Vec is overfull.
+            assert!(elem_size != 0, "capacity overflow");
+
             // current API requires us to specify size and alignment manually.
 
             let (new_cap, ptr) = if self.cap == 0 {
@@ -90,8 +102,14 @@ impl<T> Vec<T> {
     pub fn push(&mut self, elem: T) {
         if self.len == self.cap { self.grow(); }
 
-        unsafe {
-            ptr::write(self.ptr.as_ptr().offset(self.len as isize), elem);
+        if mem::size_of::<T>() == 0 {
+            // No memory traffic for a ZST: there's nothing to write, just
+            // forget it so its (nonexistent) bits are "stored".
+            mem::forget(elem);
+        } else {
+            unsafe {
+                ptr::write(self.ptr.as_ptr().offset(self.len as isize), elem);
+            }
         }
 
         // Can't fail, we'll OOM first.
@@ -103,8 +121,14 @@ impl<T> Vec<T> {
             None
         } else {
             self.len -= 1;
-            unsafe {
-                Some(ptr::read(self.ptr.as_ptr().offset(self.len as isize)))
+            if mem::size_of::<T>() == 0 {
+                // No memory traffic for a ZST: conjure one up from the
+                // dangling-but-aligned pointer instead of reading real memory.
+                unsafe { Some(ptr::read(NonNull::dangling().as_ptr())) }
+            } else {
+                unsafe {
+                    Some(ptr::read(self.ptr.as_ptr().offset(self.len as isize)))
+                }
             }
         }
     }
@@ -115,28 +139,39 @@ impl<T> Vec<T> {
         assert!(index <= self.len, "index out of bounds");
         if self.cap == self.len { self.grow(); }
 
-        unsafe {
-            if index < self.len {
-                // ptr::copy(src, dest, len): "copy from source to dest len elems"
-                ptr::copy(self.ptr.as_ptr().offset(index as isize),
-                          self.ptr.as_ptr().offset(index as isize + 1),
-                          self.len - index);
+        if mem::size_of::<T>() == 0 {
+            // No memory traffic for a ZST: no elements to shift, nothing to write.
+            mem::forget(elem);
+        } else {
+            unsafe {
+                if index < self.len {
+                    // ptr::copy(src, dest, len): "copy from source to dest len elems"
+                    ptr::copy(self.ptr.as_ptr().offset(index as isize),
+                              self.ptr.as_ptr().offset(index as isize + 1),
+                              self.len - index);
+                }
+                ptr::write(self.ptr.as_ptr().offset(index as isize), elem);
             }
-            ptr::write(self.ptr.as_ptr().offset(index as isize), elem);
-            self.len += 1;
         }
+        self.len += 1;
     }
 
     pub fn remove(&mut self, index: usize) -> T {
         // Note: `<` because it's *not* valid to remove after everything
         assert!(index < self.len, "index out of bounds");
-        unsafe {
-            self.len -= 1;
-            let result = ptr::read(self.ptr.as_ptr().offset(index as isize));
-            ptr::copy(self.ptr.as_ptr().offset(index as isize + 1),
-                      self.ptr.as_ptr().offset(index as isize),
-                      self.len - index);
-            result
+        self.len -= 1;
+        if mem::size_of::<T>() == 0 {
+            // No memory traffic for a ZST: no elements to shift, conjure the
+            // result up from the dangling-but-aligned pointer.
+            unsafe { ptr::read(NonNull::dangling().as_ptr()) }
+        } else {
+            unsafe {
+                let result = ptr::read(self.ptr.as_ptr().offset(index as isize));
+                ptr::copy(self.ptr.as_ptr().offset(index as isize + 1),
+                          self.ptr.as_ptr().offset(index as isize),
+                          self.len - index);
+                result
+            }
         }
     }
 
@@ -154,7 +189,9 @@ impl<T> Vec<T> {
                 buf: ptr,
                 cap: cap,
                 start: ptr.as_ptr(),
-                end: if cap == 0 {
+                end: if mem::size_of::<T>() == 0 {
+                    ((ptr.as_ptr() as usize) + len) as *const _
+                } else if cap == 0 {
                     // can't offset off this pointer, it's not allocated!
                     ptr.as_ptr()
                 } else {
@@ -187,15 +224,20 @@ impl<T> Iterator for IntoIter<T> {
         } else {
             unsafe {
                 let result = ptr::read(self.start);
-                self.start = self.start.offset(1);
+                self.start = if mem::size_of::<T>() == 0 {
+                    (self.start as usize + 1) as *const _
+                } else {
+                    self.start.offset(1)
+                };
                 Some(result)
             }
         }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
+        let elem_size = mem::size_of::<T>();
         let len = (self.end as usize - self.start as usize)
-                  / mem::size_of::<T>();
+                  / if elem_size == 0 { 1 } else { elem_size };
         (len, Some(len))
     }
 
@@ -207,7 +249,11 @@ impl<T> DoubleEndedIterator for IntoIter<T> {
             None
         } else {
             unsafe {
-                self.end = self.end.offset(-1);
+                self.end = if mem::size_of::<T>() == 0 {
+                    (self.end as usize - 1) as *const _
+                } else {
+                    self.end.offset(-1)
+                };
                 Some(ptr::read(self.end))
             }
         }
@@ -1,43 +1,84 @@
+// NOTE: targets a pre-stabilization `allocator_api` (`std::heap::{Alloc,
+// Layout, Global}`, `std::alloc::oom`) from before the allocator traits were
+// redesigned. That API no longer exists on any current stable or nightly
+// toolchain, so this crate cannot be built or tested here as-is.
 #![feature(allocator_api)]
+#![feature(slice_index_methods)]
 
 use std::ptr::{NonNull, self};
-use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::{cmp, mem};
+use std::ops::{Deref, DerefMut, Index, IndexMut, RangeBounds, Bound};
 use std::marker::PhantomData;
 use std::heap::{Alloc, Layout, Global};
 use std::alloc::oom;
 
+// `RawVec` (and by extension `Vec`) relies on never needing to allocate more
+// than `isize::MAX` bytes. This guards every allocation/reallocation against
+// that.
+fn alloc_guard(alloc_size: usize) {
+    if alloc_size > ::std::isize::MAX as usize {
+        panic!("Allocation too large");
+    }
+}
+
 #[derive(Debug)]
-struct RawVec<T> {
+struct RawVec<T, A: Alloc = Global> {
     ptr: NonNull<T>,
     cap: usize,
+    a: A,
+    // Tells dropck that a `RawVec<T, A>` owns `T`s, so that any borrow inside
+    // `T` (e.g. the `&'a T` of a `Vec<&'a T>`) must strictly outlive it.
+    _marker: PhantomData<T>,
 }
 
-impl<T> RawVec<T> {
+unsafe impl<T: Send, A: Alloc + Send> Send for RawVec<T, A> {}
+unsafe impl<T: Sync, A: Alloc + Sync> Sync for RawVec<T, A> {}
+
+impl<T> RawVec<T, Global> {
     fn new() -> Self {
+        RawVec::new_in(Global)
+    }
+}
+
+impl<T, A: Alloc> RawVec<T, A> {
+    fn new_in(a: A) -> Self {
         // !0 is usize::MAX. This branch should be stripped at compile time.
         let cap = if mem::size_of::<T>() == 0 { !0 } else { 0 };
 
-        RawVec { ptr: NonNull::dangling(), cap: cap }
+        RawVec { ptr: NonNull::dangling(), cap: cap, a: a, _marker: PhantomData }
     }
 
-    fn grow(&mut self) {
+    fn with_capacity_in(cap: usize, a: A) -> Self {
+        let mut buf = RawVec::new_in(a);
+        buf.grow_to(cap);
+        buf
+    }
+
+    // Grows to at least `min_cap`, doubling the existing capacity if that's
+    // already bigger (amortized growth).
+    fn grow_to(&mut self, min_cap: usize) {
+        if self.cap >= min_cap {
+            return;
+        }
+
         unsafe {
             let elem_size = mem::size_of::<T>();
 
             // since we set the capacity to usize::MAX when elem_size is
-            // 0, getting to here necessarily means the Vec is overfull.
+            // 0, having enough capacity already returned above, getting to
+            // here necessarily means the Vec is overfull.
             assert!(elem_size != 0, "capacity overflow");
 
-            let (new_cap, ptr) = if self.cap == 0 {
-                let ptr = Global.alloc(Layout::array::<T>(1).unwrap());
-                (1, ptr)
+            let new_cap = cmp::max(2 * self.cap, min_cap);
+            let new_alloc_size = new_cap.checked_mul(elem_size).unwrap_or_else(|| oom());
+            alloc_guard(new_alloc_size);
+
+            let ptr = if self.cap == 0 {
+                self.a.alloc(Layout::array::<T>(new_cap).unwrap())
             } else {
-                let new_cap = 2 * self.cap;
-                let ptr = Global.realloc(NonNull::from(self.ptr).as_opaque(),
-                                       Layout::array::<T>(self.cap).unwrap(),
-                                       Layout::array::<T>(new_cap).unwrap().size());
-                (new_cap, ptr)
+                self.a.realloc(NonNull::from(self.ptr).as_opaque(),
+                               Layout::array::<T>(self.cap).unwrap(),
+                               new_alloc_size)
             };
 
             // If allocate or reallocate fail, oom
@@ -50,15 +91,51 @@ impl<T> RawVec<T> {
             self.cap = new_cap;
         }
     }
+
+    // Reallocs down to exactly `new_cap`, or deallocates entirely when
+    // `new_cap` is 0.
+    fn shrink_to_fit(&mut self, new_cap: usize) {
+        let elem_size = mem::size_of::<T>();
+        // ZSTs never allocate, so there's nothing to shrink.
+        if elem_size == 0 || new_cap == self.cap {
+            return;
+        }
+
+        unsafe {
+            if new_cap == 0 {
+                if self.cap != 0 {
+                    self.a.dealloc(NonNull::from(self.ptr).as_opaque(),
+                                   Layout::array::<T>(self.cap).unwrap());
+                }
+                self.ptr = NonNull::dangling();
+                self.cap = 0;
+                return;
+            }
+
+            let new_alloc_size = new_cap.checked_mul(elem_size).unwrap_or_else(|| oom());
+            alloc_guard(new_alloc_size);
+
+            let ptr = self.a.realloc(NonNull::from(self.ptr).as_opaque(),
+                                     Layout::array::<T>(self.cap).unwrap(),
+                                     new_alloc_size);
+            let ptr = match ptr {
+                Ok(ptr) => ptr,
+                Err(_err) => oom(),
+            };
+
+            self.ptr = NonNull::new_unchecked(ptr.as_ptr() as *mut _);
+            self.cap = new_cap;
+        }
+    }
 }
 
-impl<T> Drop for RawVec<T> {
+impl<T, A: Alloc> Drop for RawVec<T, A> {
     fn drop(&mut self) {
         let elem_size = mem::size_of::<T>();
         if self.cap != 0 && elem_size != 0 {
             unsafe {
                 println!("drop rawvec");
-                Global.dealloc(NonNull::from(self.ptr).as_opaque(),
+                self.a.dealloc(NonNull::from(self.ptr).as_opaque(),
                              Layout::array::<T>(self.cap).unwrap());
             }
         }
@@ -66,21 +143,50 @@ impl<T> Drop for RawVec<T> {
 }
 
 #[derive(Debug)]
-pub struct Vec<T> {
-    buf: RawVec<T>,
+pub struct Vec<T, A: Alloc = Global> {
+    buf: RawVec<T, A>,
     len: usize,
 }
 
-impl<T> Vec<T> {
+unsafe impl<T: Send, A: Alloc + Send> Send for Vec<T, A> {}
+unsafe impl<T: Sync, A: Alloc + Sync> Sync for Vec<T, A> {}
+
+impl<T> Vec<T, Global> {
+    pub fn new() -> Self {
+        Vec { buf: RawVec::new(), len: 0 }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Vec::with_capacity_in(cap, Global)
+    }
+}
+
+impl<T, A: Alloc> Vec<T, A> {
     fn ptr(&self) -> *mut T { self.buf.ptr.as_ptr() }
 
     fn cap(&self) -> usize { self.buf.cap }
 
-    pub fn new() -> Self {
-        Vec { buf: RawVec::new(), len: 0 }
+    pub fn new_in(a: A) -> Self {
+        Vec { buf: RawVec::new_in(a), len: 0 }
+    }
+
+    pub fn with_capacity_in(cap: usize, a: A) -> Self {
+        Vec { buf: RawVec::with_capacity_in(cap, a), len: 0 }
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let desired_cap = self.len.checked_add(additional).expect("capacity overflow");
+        if desired_cap > self.cap() {
+            self.buf.grow_to(desired_cap);
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to_fit(self.len);
     }
+
     pub fn push(&mut self, elem: T) {
-        if self.len == self.cap() { self.buf.grow(); }
+        self.reserve(1);
 
         unsafe {
             ptr::write(self.ptr().offset(self.len as isize), elem);
@@ -103,7 +209,7 @@ impl<T> Vec<T> {
 
     pub fn insert(&mut self, index: usize, elem: T) {
         assert!(index <= self.len, "index out of bounds");
-        if self.cap() == self.len { self.buf.grow(); }
+        self.reserve(1);
 
         unsafe {
             if index < self.len {
@@ -128,7 +234,7 @@ impl<T> Vec<T> {
         }
     }
 
-    pub fn into_iter(self) -> IntoIter<T> {
+    pub fn into_iter(self) -> IntoIter<T, A> {
         unsafe {
             let iter = RawValIter::new(&self);
             let buf = ptr::read(&self.buf);
@@ -141,24 +247,43 @@ impl<T> Vec<T> {
         }
     }
 
-    pub fn drain(&mut self) -> Drain<T> {
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> Drain<T, A> {
+        let len = self.len;
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => len,
+        };
+
+        assert!(start <= end, "start drain index (is {}) should be <= end drain index (is {})", start, end);
+        assert!(end <= len, "end drain index (is {}) should be <= len (is {})", end, len);
+
         unsafe {
-            let iter = RawValIter::new(&self);
+            // this is a mem::forget safety thing. If Drain is forgotten, we
+            // want to just leak the elements that haven't been drained yet
+            // rather than double-free them, so we shorten len up front and
+            // fix it back up in Drain's destructor.
+            let range_slice = ::std::slice::from_raw_parts(self.ptr().offset(start as isize), end - start);
 
-            // this is a mem::forget safety thing. If Drain is forgotten, we just
-            // leak the whole Vec's contents. Also we need to do this *eventually*
-            // anyway, so why not do it now?
-            self.len = 0;
+            self.len = start;
 
             Drain {
-                iter: iter,
-                vec: PhantomData,
+                tail_start: end,
+                tail_len: len - end,
+                iter: RawValIter::new(range_slice),
+                vec: NonNull::from(&mut *self),
+                _marker: PhantomData,
             }
         }
     }
 }
 
-impl<T> Drop for Vec<T> {
+impl<T, A: Alloc> Drop for Vec<T, A> {
     fn drop(&mut self) {
         println!("drop vec!");
         while let Some(_) = self.pop() {}
@@ -166,7 +291,7 @@ impl<T> Drop for Vec<T> {
     }
 }
 
-impl<T> Deref for Vec<T> {
+impl<T, A: Alloc> Deref for Vec<T, A> {
     type Target = [T];
     fn deref(&self) -> &[T] {
         unsafe {
@@ -175,7 +300,7 @@ impl<T> Deref for Vec<T> {
     }
 }
 
-impl<T> DerefMut for Vec<T> {
+impl<T, A: Alloc> DerefMut for Vec<T, A> {
     fn deref_mut(&mut self) -> &mut [T] {
         unsafe {
             ::std::slice::from_raw_parts_mut(self.ptr(), self.len)
@@ -183,6 +308,60 @@ impl<T> DerefMut for Vec<T> {
     }
 }
 
+impl<T: Clone> Clone for Vec<T> {
+    fn clone(&self) -> Self {
+        let mut new = Vec::with_capacity(self.len);
+        // if `elem.clone()` panics partway through, `new`'s `Drop` only
+        // has to worry about the elements already pushed, same as `push`
+        // anywhere else.
+        for elem in self.iter() {
+            new.push(elem.clone());
+        }
+        new
+    }
+}
+
+impl<T, A: Alloc, I: ::std::slice::SliceIndex<[T]>> Index<I> for Vec<T, A> {
+    type Output = I::Output;
+    fn index(&self, index: I) -> &I::Output {
+        Index::index(&**self, index)
+    }
+}
+
+impl<T, A: Alloc, I: ::std::slice::SliceIndex<[T]>> IndexMut<I> for Vec<T, A> {
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        IndexMut::index_mut(&mut **self, index)
+    }
+}
+
+impl<T: PartialEq, A: Alloc> PartialEq for Vec<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T> ::std::iter::FromIterator<T> for Vec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        let mut v = Vec::with_capacity(lower);
+        for elem in iter {
+            v.push(elem);
+        }
+        v
+    }
+}
+
+impl<T, A: Alloc> ::std::iter::Extend<T> for Vec<T, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        self.reserve(lower);
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
 
 
 
@@ -253,22 +432,25 @@ impl<T> DoubleEndedIterator for RawValIter<T> {
 
 
 
-pub struct IntoIter<T> {
-    _buf: RawVec<T>, // we don't actually care about this. Just need it to live.
+pub struct IntoIter<T, A: Alloc = Global> {
+    _buf: RawVec<T, A>, // we don't actually care about this. Just need it to live.
     iter: RawValIter<T>,
 }
 
-impl<T> Iterator for IntoIter<T> {
+unsafe impl<T: Send, A: Alloc + Send> Send for IntoIter<T, A> {}
+unsafe impl<T: Sync, A: Alloc + Sync> Sync for IntoIter<T, A> {}
+
+impl<T, A: Alloc> Iterator for IntoIter<T, A> {
     type Item = T;
     fn next(&mut self) -> Option<T> { self.iter.next() }
     fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
 }
 
-impl<T> DoubleEndedIterator for IntoIter<T> {
+impl<T, A: Alloc> DoubleEndedIterator for IntoIter<T, A> {
     fn next_back(&mut self) -> Option<T> { self.iter.next_back() }
 }
 
-impl<T> Drop for IntoIter<T> {
+impl<T, A: Alloc> Drop for IntoIter<T, A> {
     fn drop(&mut self) {
         for _ in &mut *self {}
     }
@@ -277,25 +459,43 @@ impl<T> Drop for IntoIter<T> {
 
 
 
-pub struct Drain<'a, T: 'a> {
-    vec: PhantomData<&'a mut Vec<T>>,
+pub struct Drain<'a, T: 'a, A: Alloc = Global> {
+    tail_start: usize,
+    tail_len: usize,
     iter: RawValIter<T>,
+    vec: NonNull<Vec<T, A>>,
+    _marker: PhantomData<&'a mut Vec<T, A>>,
 }
 
-impl<'a, T> Iterator for Drain<'a, T> {
+impl<'a, T, A: Alloc> Iterator for Drain<'a, T, A> {
     type Item = T;
-    fn next(&mut self) -> Option<T> { self.iter.next_back() }
+    fn next(&mut self) -> Option<T> { self.iter.next() }
     fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
 }
 
-impl<'a, T> DoubleEndedIterator for Drain<'a, T> {
+impl<'a, T, A: Alloc> DoubleEndedIterator for Drain<'a, T, A> {
     fn next_back(&mut self) -> Option<T> { self.iter.next_back() }
 }
 
-impl<'a, T> Drop for Drain<'a, T> {
+impl<'a, T, A: Alloc> Drop for Drain<'a, T, A> {
     fn drop(&mut self) {
         // pre-drain the iter
         for _ in &mut self.iter {}
+
+        if self.tail_len > 0 {
+            unsafe {
+                let source_vec = self.vec.as_mut();
+                // memmove back the untouched tail, then fix up .len
+                let start = source_vec.len;
+                let tail = self.tail_start;
+                if tail != start {
+                    let src = source_vec.ptr().offset(tail as isize);
+                    let dst = source_vec.ptr().offset(start as isize);
+                    ptr::copy(src, dst, self.tail_len);
+                }
+                source_vec.len = start + self.tail_len;
+            }
+        }
     }
 }
 